@@ -0,0 +1,173 @@
+//! Negotiation of the `permessage-deflate` (RFC 7692) `Sec-WebSocket-Extensions` offer.
+
+/// The agreed parameters for the `permessage-deflate` extension, negotiated during
+/// `Request::accept_with_deflate()` and surfaced on the accepted `Response`.
+#[derive(PartialEq, Clone, Debug)]
+pub struct PermessageDeflateParams {
+	/// Whether the client promised not to use a sliding window across messages.
+	pub client_no_context_takeover: bool,
+	/// Whether the server will avoid using a sliding window across messages.
+	pub server_no_context_takeover: bool,
+	/// The LZ77 window size (8...15) the client will use when deflating.
+	pub client_max_window_bits: u8,
+	/// The LZ77 window size (8...15) the server will use when deflating.
+	pub server_max_window_bits: u8,
+}
+
+impl Default for PermessageDeflateParams {
+	fn default() -> PermessageDeflateParams {
+		PermessageDeflateParams {
+			client_no_context_takeover: false,
+			server_no_context_takeover: false,
+			client_max_window_bits: 15,
+			server_max_window_bits: 15,
+		}
+	}
+}
+
+/// Split a single raw extension offer (e.g. `"permessage-deflate; client_max_window_bits"`)
+/// into its name and an ordered list of parameter name/value pairs.
+fn parse_offer(raw: &str) -> (String, Vec<(String, Option<String>)>) {
+	let mut parts = raw.split(';').map(|part| part.trim());
+	let name = parts.next().unwrap_or("").to_string();
+
+	let params = parts.filter(|part| !part.is_empty()).map(|param| {
+		match param.find('=') {
+			Some(index) => {
+				let (key, value) = param.split_at(index);
+				(key.trim().to_string(), Some(value[1..].trim().trim_matches('"').to_string()))
+			}
+			None => (param.to_string(), None)
+		}
+	}).collect();
+
+	(name, params)
+}
+
+/// Try to negotiate `permessage-deflate` against the client's offered extensions, returning the
+/// agreed parameters for the first acceptable offer.
+///
+/// Offers with a `*_max_window_bits` value outside `8...15`, or with a parameter this server
+/// doesn't recognise, are skipped in favour of the next offer.
+pub fn negotiate_permessage_deflate(offers: &[String]) -> Option<PermessageDeflateParams> {
+	'offers: for raw in offers.iter() {
+		let (name, params) = parse_offer(raw);
+		if name != "permessage-deflate" {
+			continue;
+		}
+
+		let mut negotiated = PermessageDeflateParams::default();
+
+		for (key, value) in params.into_iter() {
+			match key.as_slice() {
+				"client_no_context_takeover" => negotiated.client_no_context_takeover = true,
+				"server_no_context_takeover" => negotiated.server_no_context_takeover = true,
+				"client_max_window_bits" => {
+					match value {
+						None => { }
+						Some(bits) => {
+							match bits.parse().ok() {
+								Some(bits) if bits >= 8 && bits <= 15 => negotiated.client_max_window_bits = bits,
+								_ => { continue 'offers; }
+							}
+						}
+					}
+				}
+				"server_max_window_bits" => {
+					match value.and_then(|bits| bits.parse().ok()) {
+						Some(bits) if bits >= 8 && bits <= 15 => negotiated.server_max_window_bits = bits,
+						_ => { continue 'offers; }
+					}
+				}
+				_ => { continue 'offers; }
+			}
+		}
+
+		return Some(negotiated);
+	}
+	None
+}
+
+/// Format negotiated `permessage-deflate` parameters back into the single extension token
+/// that goes in the accepted response's `Sec-WebSocket-Extensions` header.
+pub fn format_permessage_deflate(params: &PermessageDeflateParams) -> String {
+	let mut tokens = vec!["permessage-deflate".to_string()];
+
+	if params.server_no_context_takeover {
+		tokens.push("server_no_context_takeover".to_string());
+	}
+	if params.client_no_context_takeover {
+		tokens.push("client_no_context_takeover".to_string());
+	}
+	if params.server_max_window_bits != 15 {
+		tokens.push(format!("server_max_window_bits={}", params.server_max_window_bits));
+	}
+	if params.client_max_window_bits != 15 {
+		tokens.push(format!("client_max_window_bits={}", params.client_max_window_bits));
+	}
+
+	tokens.connect("; ")
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_negotiate_plain_offer() {
+		let offers = vec!["permessage-deflate".to_string()];
+		assert_eq!(negotiate_permessage_deflate(offers.as_slice()), Some(PermessageDeflateParams::default()));
+	}
+
+	#[test]
+	fn test_negotiate_with_params() {
+		let offers = vec!["permessage-deflate; server_no_context_takeover; client_max_window_bits=10".to_string()];
+		let params = negotiate_permessage_deflate(offers.as_slice()).unwrap();
+		assert!(params.server_no_context_takeover);
+		assert_eq!(params.client_max_window_bits, 10);
+		assert_eq!(params.server_max_window_bits, 15);
+	}
+
+	#[test]
+	fn test_negotiate_rejects_out_of_range_server_bits() {
+		let offers = vec!["permessage-deflate; server_max_window_bits=20".to_string()];
+		assert_eq!(negotiate_permessage_deflate(offers.as_slice()), None);
+	}
+
+	#[test]
+	fn test_negotiate_rejects_malformed_client_bits() {
+		let offers = vec!["permessage-deflate; client_max_window_bits=banana".to_string()];
+		assert_eq!(negotiate_permessage_deflate(offers.as_slice()), None);
+	}
+
+	#[test]
+	fn test_negotiate_accepts_bare_client_bits() {
+		let offers = vec!["permessage-deflate; client_max_window_bits".to_string()];
+		let params = negotiate_permessage_deflate(offers.as_slice()).unwrap();
+		assert_eq!(params.client_max_window_bits, 15);
+	}
+
+	#[test]
+	fn test_negotiate_skips_unknown_offers() {
+		let offers = vec!["x-unknown-extension".to_string(), "permessage-deflate".to_string()];
+		assert!(negotiate_permessage_deflate(offers.as_slice()).is_some());
+	}
+
+	#[test]
+	fn test_negotiate_no_offers() {
+		let offers: Vec<String> = Vec::new();
+		assert_eq!(negotiate_permessage_deflate(offers.as_slice()), None);
+	}
+
+	#[test]
+	fn test_format_permessage_deflate() {
+		let mut params = PermessageDeflateParams::default();
+		params.server_no_context_takeover = true;
+		params.client_max_window_bits = 10;
+
+		assert_eq!(
+			format_permessage_deflate(&params).as_slice(),
+			"permessage-deflate; server_no_context_takeover; client_max_window_bits=10"
+		);
+	}
+}
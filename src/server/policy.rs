@@ -0,0 +1,82 @@
+//! Opt-in `Origin` allow-list enforcement, consulted by `Request::accept_with_origin_policy()`.
+
+use header::Origin;
+
+/// An allow-list policy for the `Origin` header. A request that sends no `Origin` header is
+/// always allowed through.
+pub struct OriginPolicy {
+	allowed: Vec<String>,
+	predicate: Option<Box<Fn(&Origin) -> bool + Send + Sync>>,
+}
+
+impl OriginPolicy {
+	/// Allow exactly the given origin strings (e.g. `"http://example.com"`).
+	pub fn allow_list(origins: Vec<String>) -> OriginPolicy {
+		OriginPolicy {
+			allowed: origins,
+			predicate: None,
+		}
+	}
+
+	/// Allow whatever origins the given predicate accepts.
+	pub fn allow_if<F>(predicate: F) -> OriginPolicy where F: Fn(&Origin) -> bool + Send + Sync + 'static {
+		OriginPolicy {
+			allowed: Vec::new(),
+			predicate: Some(Box::new(predicate)),
+		}
+	}
+
+	/// Also allow whatever origins `predicate` accepts, on top of this policy's exact-match
+	/// list, so a policy can combine both mechanisms.
+	pub fn also_allow_if<F>(mut self, predicate: F) -> OriginPolicy where F: Fn(&Origin) -> bool + Send + Sync + 'static {
+		self.predicate = Some(Box::new(predicate));
+		self
+	}
+
+	/// Whether `origin` satisfies this policy.
+	pub fn allows(&self, origin: &Origin) -> bool {
+		if self.allowed.iter().any(|allowed| allowed.as_slice() == origin.as_slice()) {
+			return true;
+		}
+
+		match self.predicate {
+			Some(ref predicate) => predicate(origin),
+			None => false,
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use header::Origin;
+
+	#[test]
+	fn test_allow_list_matches_exact_origin() {
+		let policy = OriginPolicy::allow_list(vec!["http://example.com".to_string()]);
+		assert!(policy.allows(&Origin("http://example.com".to_string())));
+	}
+
+	#[test]
+	fn test_allow_list_rejects_other_origins() {
+		let policy = OriginPolicy::allow_list(vec!["http://example.com".to_string()]);
+		assert!(!policy.allows(&Origin("http://evil.com".to_string())));
+	}
+
+	#[test]
+	fn test_allow_if_predicate() {
+		let policy = OriginPolicy::allow_if(|origin| origin.as_slice().ends_with(".example.com"));
+		assert!(policy.allows(&Origin("http://foo.example.com".to_string())));
+		assert!(!policy.allows(&Origin("http://evil.com".to_string())));
+	}
+
+	#[test]
+	fn test_allow_list_combined_with_predicate() {
+		let policy = OriginPolicy::allow_list(vec!["http://example.com".to_string()])
+			.also_allow_if(|origin| origin.as_slice().ends_with(".example.com"));
+
+		assert!(policy.allows(&Origin("http://example.com".to_string())));
+		assert!(policy.allows(&Origin("http://foo.example.com".to_string())));
+		assert!(!policy.allows(&Origin("http://evil.com".to_string())));
+	}
+}
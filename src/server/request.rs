@@ -1,6 +1,8 @@
 //! The server-side WebSocket request.
 
 use server::Response;
+use server::extensions::{negotiate_permessage_deflate, format_permessage_deflate};
+use server::policy::OriginPolicy;
 use result::{WebSocketResult, WebSocketError};
 use header::{WebSocketKey, WebSocketVersion, WebSocketProtocol, WebSocketExtensions, Origin};
 
@@ -11,11 +13,14 @@ use hyper::status::StatusCode;
 use hyper::header::Headers;
 use hyper::header::{Connection, ConnectionOption};
 use hyper::header::{Upgrade, Protocol};
+use hyper::header::{Cookie, CookiePair};
 use hyper::http::read_request_line;
 use hyper::method::Method;
 
 use unicase::UniCase;
 
+use std::io::{IoErrorKind, MemReader};
+
 /// Represents a server-side (incoming) request.
 pub struct Request<R: Reader, W: Writer> {
 	/// The target URI for this request.
@@ -52,6 +57,14 @@ impl<R: Reader, W: Writer> Request<R, W> {
 	pub fn origin(&self) -> Option<&Origin> {
 		self.headers.get()
 	}
+	/// Parses the incoming `Cookie` header into name/value pairs.
+	///
+	/// Returns an empty `Vec` if the client sent no `Cookie` header, so applications can
+	/// authenticate or resume a session at connect time without handling `self.headers` by
+	/// hand.
+	pub fn cookies(&self) -> Vec<(String, String)> {
+		parse_cookie_header(&self.headers)
+	}
 	/// Returns a reference to the inner Reader.
 	pub fn get_reader(&self) -> &R {
 		&self.reader
@@ -73,20 +86,13 @@ impl<R: Reader, W: Writer> Request<R, W> {
 		(self.reader, self.writer)
 	}
 	/// Reads an inbound request.
-	/// 
+	///
 	/// This method is used within servers, and returns an inbound WebSocketRequest.
 	/// An error will be returned if the request cannot be read, or is not a valid HTTP request.
 	pub fn read(reader: R, writer: W) -> WebSocketResult<Request<R, W>> {
 		let mut reader = reader;
-		let (method, uri, version) = try!(read_request_line(&mut reader));
-		
-		match method {
-			Method::Get => { },
-			_ => { return Err(WebSocketError::RequestError("Request method must be GET".to_string())); }
-		}
-		
-        let headers = try!(Headers::from_raw(&mut reader));
-		
+		let (uri, version, headers) = try!(read_head(&mut reader));
+
 		Ok(Request {
 			url: uri,
 			version: version,
@@ -137,6 +143,11 @@ impl<R: Reader, W: Writer> Request<R, W> {
 	///
 	/// This function calls `validate()` on the request, and if the request is found to be invalid,
 	/// generates a response with a Bad Request status code.
+	///
+	/// No subprotocol is negotiated; use `accept_with_protocols()` if the client offered any
+	/// and the server needs to pick one. No compression is negotiated either; use
+	/// `accept_with_deflate()` once the caller is actually prepared to handle RSV1-flagged
+	/// frames.
 	pub fn accept(self) -> Response<R, W> {
 		match self.validate() {
 			Ok(()) => { }
@@ -144,12 +155,275 @@ impl<R: Reader, W: Writer> Request<R, W> {
 		}
 		Response::new(self)
 	}
-	
-	/// Fail this request by generating a Bad Request response
-	pub fn fail(self) -> Response<R, W> {
+
+	/// Accept this request, additionally negotiating `permessage-deflate` from
+	/// `Sec-WebSocket-Extensions` if the client offered it.
+	///
+	/// The agreed parameters are reflected back in the response header and surfaced on
+	/// `Response::deflate`; offers this server doesn't understand are skipped so the handshake
+	/// still completes uncompressed. This is opt-in rather than part of `accept()` because
+	/// negotiating it is only half the protocol: the caller is responsible for actually
+	/// RSV1-flagging and raw-deflating frames on a connection this agrees to, since no frame
+	/// layer in this crate does that yet.
+	pub fn accept_with_deflate(self) -> Response<R, W> {
+		let deflate = self.extensions().and_then(|offered| negotiate_permessage_deflate(offered));
+
+		let mut response = self.accept();
+		if response.status == StatusCode::SwitchingProtocols {
+			if let Some(ref params) = deflate {
+				response.headers.set(WebSocketExtensions(vec![format_permessage_deflate(params)]));
+			}
+			response.deflate = deflate;
+		}
+		response
+	}
+
+	/// Accept this request, negotiating a subprotocol from `supported`.
+	///
+	/// The protocols the client offered via `Sec-WebSocket-Protocol` are intersected with
+	/// `supported`, preserving the client's preference order, and the first match is reflected
+	/// back in the response. If the client offered no protocol the server supports (or none at
+	/// all), the response simply omits the `Sec-WebSocket-Protocol` header rather than failing
+	/// the handshake.
+	pub fn accept_with_protocols(self, supported: &[&str]) -> Response<R, W> {
+		let chosen = self.protocol().and_then(|offered| {
+			offered.iter().find(|p| supported.contains(&p.as_slice())).cloned()
+		});
+
+		let mut response = self.accept();
+		if response.status == StatusCode::SwitchingProtocols {
+			if let Some(protocol) = chosen {
+				response.headers.set(WebSocketProtocol(vec![protocol]));
+			}
+		}
+		response
+	}
+
+	/// Accept this request only if its `Origin` header, when present, is allowed by `policy`.
+	///
+	/// Requests that send no `Origin` header are unaffected; this is purely opt-in access
+	/// control for servers that configure a policy. A disallowed origin fails the handshake
+	/// with `403 Forbidden`, rather than the `400 Bad Request` used for other validation
+	/// failures.
+	pub fn accept_with_origin_policy(self, policy: &OriginPolicy) -> Response<R, W> {
+		if let Some(origin) = self.origin() {
+			if !policy.allows(origin) {
+				return self.fail_with(StatusCode::Forbidden);
+			}
+		}
+		self.accept()
+	}
+
+	/// Fail this request by generating a response with the given status code and no headers.
+	pub fn fail_with(self, status: StatusCode) -> Response<R, W> {
 		let mut response = Response::new(self);
-		response.status = StatusCode::BadRequest;
+		response.status = status;
 		response.headers = Headers::new();
 		response
 	}
-}
\ No newline at end of file
+
+	/// Fail this request by generating a Bad Request response
+	pub fn fail(self) -> Response<R, W> {
+		self.fail_with(StatusCode::BadRequest)
+	}
+}
+
+/// The outcome of driving a `MidHandshake` forward by one `try_read()`.
+pub enum HandshakeState<R: Reader, W: Writer> {
+	/// The full header block hasn't arrived on the socket yet; resume with this machine once
+	/// it becomes readable again.
+	Incomplete(MidHandshake<R, W>),
+	/// The request line and all headers have been read.
+	Done(Request<R, W>),
+}
+
+/// Reads a server-side handshake request incrementally, for use on non-blocking sockets or
+/// inside an async reactor.
+///
+/// Unlike `Request::read`, which assumes `reader` will block until the whole request head is
+/// available, `MidHandshake` buffers whatever bytes are currently available and re-attempts to
+/// parse a request line and headers out of that buffer, returning `HandshakeState::Incomplete`
+/// instead of blocking when the header block (terminated by `\r\n\r\n`) hasn't fully arrived.
+/// `WouldBlock` I/O errors are treated the same way rather than as fatal.
+pub struct MidHandshake<R: Reader, W: Writer> {
+	reader: R,
+	writer: W,
+	buffer: Vec<u8>,
+}
+
+impl<R: Reader, W: Writer> MidHandshake<R, W> {
+	/// Start a new incremental handshake read over `reader`/`writer`.
+	pub fn new(reader: R, writer: W) -> MidHandshake<R, W> {
+		MidHandshake {
+			reader: reader,
+			writer: writer,
+			buffer: Vec::new(),
+		}
+	}
+
+	/// Attempt to make progress on the handshake, consuming whatever bytes are currently
+	/// available on the reader.
+	pub fn try_read(mut self) -> WebSocketResult<HandshakeState<R, W>> {
+		loop {
+			if has_header_terminator(&self.buffer) {
+				return self.finish().map(HandshakeState::Done);
+			}
+
+			// Read one byte at a time rather than in chunks: overreading past the header
+			// terminator would swallow the start of whatever the client sends next (frame
+			// data), which would have to be spliced back in front of `self.reader` for the
+			// `Request` we hand back, and this era's `Reader` gives us no cheap way to do that.
+			let mut byte = [0u8];
+			match self.reader.read(&mut byte) {
+				Ok(_) => { self.buffer.push(byte[0]); }
+				Err(ref error) if error.kind == IoErrorKind::WouldBlock => {
+					return Ok(HandshakeState::Incomplete(self));
+				}
+				Err(ref error) if error.kind == IoErrorKind::EndOfFile => {
+					return Err(WebSocketError::RequestError("Connection closed during handshake".to_string()));
+				}
+				Err(error) => {
+					return Err(WebSocketError::RequestError(format!("{}", error)));
+				}
+			}
+		}
+	}
+
+	/// Parse the buffered header block and hand the (now positioned right past the headers)
+	/// reader and writer over to a fresh `Request`.
+	fn finish(self) -> WebSocketResult<Request<R, W>> {
+		let MidHandshake { reader, writer, buffer } = self;
+		let mut head = MemReader::new(buffer);
+		let (uri, version, headers) = try!(read_head(&mut head));
+
+		Ok(Request {
+			url: uri,
+			version: version,
+			headers: headers,
+			reader: reader,
+			writer: writer,
+		})
+	}
+}
+
+/// Whether `buffer` ends with the `\r\n\r\n` that terminates an HTTP header block.
+fn has_header_terminator(buffer: &[u8]) -> bool {
+	buffer.ends_with(b"\r\n\r\n")
+}
+
+/// Parse the `Cookie` header, if any, into name/value pairs.
+fn parse_cookie_header(headers: &Headers) -> Vec<(String, String)> {
+	match headers.get::<Cookie>() {
+		Some(&Cookie(ref pairs)) => pairs.iter().map(|pair| (pair.name.clone(), pair.value.clone())).collect(),
+		None => Vec::new(),
+	}
+}
+
+/// Read the request line and headers off `reader`, verifying the method is `GET`.
+///
+/// Shared by `Request::read` (reading straight off a blocking reader) and
+/// `MidHandshake::finish` (reading off the buffered header block), so the two can't drift.
+fn read_head<Rd: Reader>(reader: &mut Rd) -> WebSocketResult<(RequestUri, HttpVersion, Headers)> {
+	let (method, uri, version) = try!(read_request_line(reader));
+
+	match method {
+		Method::Get => { },
+		_ => { return Err(WebSocketError::RequestError("Request method must be GET".to_string())); }
+	}
+
+	let headers = try!(Headers::from_raw(reader));
+
+	Ok((uri, version, headers))
+}
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use std::io::{IoError, IoErrorKind, IoResult, MemWriter};
+
+	/// A `Reader` that yields pre-scripted chunks of bytes, one byte at a time, signalling
+	/// `WouldBlock` between chunks and `EndOfFile` once they've all been consumed.
+	struct ScriptedReader {
+		chunks: Vec<Vec<u8>>,
+	}
+
+	impl Reader for ScriptedReader {
+		fn read(&mut self, buf: &mut [u8]) -> IoResult<uint> {
+			if self.chunks.is_empty() {
+				return Err(IoError { kind: IoErrorKind::EndOfFile, desc: "eof", detail: None });
+			}
+			if self.chunks[0].is_empty() {
+				self.chunks.remove(0);
+				if self.chunks.is_empty() {
+					return Err(IoError { kind: IoErrorKind::EndOfFile, desc: "eof", detail: None });
+				}
+				return Err(IoError { kind: IoErrorKind::WouldBlock, desc: "would block", detail: None });
+			}
+			buf[0] = self.chunks[0].remove(0);
+			Ok(1)
+		}
+	}
+
+	#[test]
+	fn test_mid_handshake_resumes_across_would_block() {
+		let head = b"GET / HTTP/1.1\r\nHost: example.com\r\n\r\n".to_vec();
+		let split = head.len() / 2;
+		let reader = ScriptedReader { chunks: vec![head[..split].to_vec(), head[split..].to_vec()] };
+
+		let handshake = MidHandshake::new(reader, MemWriter::new());
+		let handshake = match handshake.try_read() {
+			Ok(HandshakeState::Incomplete(machine)) => machine,
+			other => panic!("expected Incomplete, got {:?}", other.is_ok()),
+		};
+
+		match handshake.try_read() {
+			Ok(HandshakeState::Done(request)) => {
+				assert_eq!(request.version, HttpVersion::Http11);
+			}
+			other => panic!("expected Done, got {:?}", other.is_ok()),
+		}
+	}
+
+	#[test]
+	fn test_mid_handshake_errors_on_eof() {
+		let reader = ScriptedReader { chunks: vec![b"GET / HTTP/1.1\r\n".to_vec()] };
+		let handshake = MidHandshake::new(reader, MemWriter::new());
+
+		match handshake.try_read() {
+			Err(WebSocketError::RequestError(ref message)) => {
+				assert_eq!(message.as_slice(), "Connection closed during handshake");
+			}
+			other => panic!("expected a RequestError, got {:?}", other.is_ok()),
+		}
+	}
+
+	#[test]
+	fn test_accept_with_protocols_omits_header_on_failed_handshake() {
+		// No Sec-WebSocket-Version/Key/Upgrade/Connection, so validate() fails before
+		// accept_with_protocols() ever gets to pick a subprotocol.
+		let head = b"GET / HTTP/1.1\r\nHost: example.com\r\nSec-WebSocket-Protocol: chat\r\n\r\n".to_vec();
+		let handshake = MidHandshake::new(ScriptedReader { chunks: vec![head] }, MemWriter::new());
+		let request = match handshake.try_read() {
+			Ok(HandshakeState::Done(request)) => request,
+			other => panic!("expected Done, got {:?}", other.is_ok()),
+		};
+
+		let response = request.accept_with_protocols(&["chat"]);
+		assert_eq!(response.status, StatusCode::BadRequest);
+		assert!(response.headers.get::<WebSocketProtocol>().is_none());
+	}
+
+	#[test]
+	fn test_parse_cookie_header() {
+		let mut headers = Headers::new();
+		headers.set(Cookie(vec![CookiePair::new("session".to_string(), "abc123".to_string())]));
+
+		let cookies = parse_cookie_header(&headers);
+		assert_eq!(cookies, vec![("session".to_string(), "abc123".to_string())]);
+	}
+
+	#[test]
+	fn test_parse_cookie_header_absent() {
+		let headers = Headers::new();
+		assert_eq!(parse_cookie_header(&headers), Vec::new());
+	}
+}
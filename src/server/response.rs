@@ -0,0 +1,54 @@
+//! The server-side WebSocket response.
+
+use server::Request;
+use server::extensions::PermessageDeflateParams;
+
+use hyper::status::StatusCode;
+use hyper::header::Headers;
+use hyper::header::{SetCookie, CookiePair};
+
+/// Represents a server-side (outgoing) response to a WebSocket handshake.
+pub struct Response<R: Reader, W: Writer> {
+	/// The HTTP status of this response.
+	pub status: StatusCode,
+
+	/// The headers of this response.
+	pub headers: Headers,
+
+	/// The `permessage-deflate` parameters negotiated for this connection, if any were agreed
+	/// on during `Request::accept_with_deflate()`.
+	pub deflate: Option<PermessageDeflateParams>,
+
+	request: Request<R, W>,
+}
+
+impl<R: Reader, W: Writer> Response<R, W> {
+	/// Create a new `101 Switching Protocols` response for the given request.
+	pub fn new(request: Request<R, W>) -> Response<R, W> {
+		Response {
+			status: StatusCode::SwitchingProtocols,
+			headers: Headers::new(),
+			deflate: None,
+			request: request,
+		}
+	}
+
+	/// Returns a reference to the request this response answers.
+	pub fn get_request(&self) -> &Request<R, W> {
+		&self.request
+	}
+
+	/// Queue a `Set-Cookie` header to be written alongside the `101 Switching Protocols`
+	/// response.
+	///
+	/// Can be called more than once to set several cookies; each call appends another pair
+	/// rather than replacing the ones already queued.
+	pub fn set_cookie(&mut self, name: &str, value: &str) {
+		let mut pairs = match self.headers.get::<SetCookie>() {
+			Some(&SetCookie(ref existing)) => existing.clone(),
+			None => Vec::new(),
+		};
+		pairs.push(CookiePair::new(name.to_string(), value.to_string()));
+		self.headers.set(SetCookie(pairs));
+	}
+}